@@ -1,13 +1,20 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
-use std::{fmt, io};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{cmp, fmt, io};
 
-use futures::{Async, Poll};
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{Either, Executor};
+use futures::sync::{mpsc, oneshot};
+use h2;
 use http;
 use rand;
-use std::sync::Arc;
+use tokio_timer::Delay;
 use tower::{self, Service};
 use tower_balance::{self, choose, load, Balance};
-use tower_buffer::{Buffer, Error as BufferError};
 use tower_discover::{Change, Discover};
 use tower_in_flight_limit::{InFlightLimit, Error as InFlightLimitError};
 use tower_h2;
@@ -18,6 +25,7 @@ use bind::{self, Bind, Protocol};
 use control::{self, discovery};
 use control::discovery::Bind as BindTrait;
 use ctx;
+use dns;
 use fully_qualified_authority::FullyQualifiedAuthority;
 use timeout::{NewTimeout, Timeout, TimeoutError};
 
@@ -29,6 +37,10 @@ pub struct Outbound<B> {
     default_namespace: Option<String>,
     default_zone: Option<String>,
     timeout: NewTimeout,
+    circuit_breaker: CircuitBreakerConfig,
+    dns: dns::Resolver,
+    dns_config: DnsConfig,
+    buffer: BufferConfig,
 }
 
 const MAX_IN_FLIGHT: usize = 10_000;
@@ -40,7 +52,11 @@ impl<B> Outbound<B> {
                discovery: control::Control,
                default_namespace: Option<String>,
                default_zone: Option<String>,
-               timeout: NewTimeout,)
+               timeout: NewTimeout,
+               circuit_breaker: CircuitBreakerConfig,
+               dns: dns::Resolver,
+               dns_config: DnsConfig,
+               buffer: BufferConfig,)
                -> Outbound<B> {
         Self {
             bind,
@@ -48,16 +64,55 @@ impl<B> Outbound<B> {
             default_namespace,
             default_zone,
             timeout,
+            circuit_breaker,
+            dns,
+            dns_config,
+            buffer,
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Destination {
-    LocalSvc(FullyQualifiedAuthority),
+    /// A local service, along with the original destination of the
+    /// connection, if any. The original destination is kept around so
+    /// that we can fall back to it if discovery yields no endpoints.
+    LocalSvc(FullyQualifiedAuthority, Option<SocketAddr>),
+    /// An external service addressed by a DNS name rather than an IP
+    /// literal, along with the original destination of the connection
+    /// (the single address the client's own resolver picked), kept
+    /// around as a fallback for the same reason as `LocalSvc`'s.
+    ExternalDns(Name, Option<SocketAddr>),
     External(SocketAddr),
 }
 
+/// A DNS name and port to resolve for an `ExternalDns` destination.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Name {
+    host: String,
+    port: u16,
+}
+
+impl Name {
+    /// Reads a DNS name and port out of a request authority, or returns
+    /// `None` if the authority's host is an IP literal -- those are
+    /// handled by the plain `External` path instead.
+    fn from_authority(authority: &http::uri::Authority) -> Option<Self> {
+        let host = authority.host();
+        if host.parse::<::std::net::IpAddr>().is_ok() {
+            return None;
+        }
+        let port = authority.port_part().map(|p| p.as_u16()).unwrap_or(80);
+        Some(Name { host: host.to_owned(), port })
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
 impl<B> Recognize for Outbound<B>
 where
     B: tower_h2::Body + 'static,
@@ -67,10 +122,10 @@ where
     type Error = <Self::Service as tower::Service>::Error;
     type Key = (Destination, Protocol);
     type RouteError = ();
-    type Service = LogErrors<Timeout<InFlightLimit<Buffer<Balance<
-        load::WithPendingRequests<Discovery<B>>,
+    type Service = LogErrors<MapErrBoxed<Timeout<MapErrBoxed<InFlightLimit<MapErrBoxed<BoundedBuffer<MapErrBoxed<OrigDstFallback<MapErrBoxed<Balance<
+        load::WithPendingRequests<CircuitBreaking<Discovery<ReplayBody<B>>>>,
         choose::PowerOfTwoChoices<rand::ThreadRng>
-    >>>>>;
+    >>, B>>>>>>>>>>;
 
     fn recognize(&self, req: &Self::Request) -> Option<Self::Key> {
         let local = req.uri().authority_part().and_then(|authority| {
@@ -81,21 +136,26 @@ where
 
         });
 
-        // If we can't fully qualify the authority as a local service,
-        // and there is no original dst, then we have nothing! In that
-        // case, we return `None`, which results an "unrecognized" error.
+        let orig_dst = req.extensions()
+            .get::<Arc<ctx::transport::Server>>()
+            .and_then(|ctx| {
+                ctx.orig_dst_if_not_local()
+            });
+
+        // If we can't fully qualify the authority as a local service, its
+        // host isn't a DNS name, and there is no original dst, then we have
+        // nothing! In that case, we return `None`, which results an
+        // "unrecognized" error.
         //
         // In practice, this shouldn't ever happen, since we expect the proxy
         // to be run on Linux servers, with iptables setup, so there should
         // always be an original destination.
+        let dns_name = req.uri().authority_part().and_then(Name::from_authority);
         let dest = if let Some(local) = local {
-            Destination::LocalSvc(local)
+            Destination::LocalSvc(local, orig_dst)
+        } else if let Some(name) = dns_name {
+            Destination::ExternalDns(name, orig_dst)
         } else {
-            let orig_dst = req.extensions()
-                .get::<Arc<ctx::transport::Server>>()
-                .and_then(|ctx| {
-                    ctx.orig_dst_if_not_local()
-                });
             Destination::External(orig_dst?)
         };
 
@@ -110,12 +170,16 @@ where
     /// Builds a dynamic, load balancing service.
     ///
     /// Resolves the authority in service discovery and initializes a service that buffers
-    /// and load balances requests across.
+    /// and load balances requests across. If discovery yields no endpoints for a local
+    /// service, requests fall back to the connection's original destination until
+    /// discovery catches up.
     ///
-    /// # TODO
-    ///
-    /// Buffering is currently unbounded and does not apply timeouts. This must be
-    /// changed.
+    /// The buffer in front of the balancer is bounded: once `self.buffer`'s
+    /// capacity is queued, further requests are shed with an overloaded
+    /// error rather than growing the queue without limit, and a request
+    /// that's waited longer than `self.buffer`'s `max_wait` is dropped
+    /// from the queue with a timeout error before it ever reaches the
+    /// `Balance`.
     fn bind_service(
         &mut self,
         key: &Self::Key,
@@ -123,35 +187,75 @@ where
         let &(ref dest, protocol) = key;
         debug!("building outbound {:?} client to {:?}", protocol, dest);
 
-        let resolve = match *dest {
-            Destination::LocalSvc(ref authority) => {
-                Discovery::LocalSvc(self.discovery.resolve(
-                    authority,
-                    self.bind.clone().with_protocol(protocol),
-                ))
+        let bind = self.bind.clone().with_protocol(protocol);
+
+        let (resolve, orig_dst) = match *dest {
+            Destination::LocalSvc(ref authority, orig_dst) => {
+                let local = LocalSvcDiscovery::new(
+                    authority.clone(),
+                    self.discovery.clone(),
+                    bind.clone(),
+                    self.circuit_breaker,
+                );
+                (Discovery::LocalSvc(local), orig_dst)
+            },
+            Destination::ExternalDns(ref name, orig_dst) => {
+                let dns = DnsDiscovery::new(
+                    name.clone(),
+                    self.dns.clone(),
+                    self.dns_config,
+                    bind.clone(),
+                    self.circuit_breaker,
+                );
+                (Discovery::Dns(dns), orig_dst)
             },
             Destination::External(addr) => {
-                Discovery::External(Some((addr, self.bind.clone().with_protocol(protocol))))
+                let pending = ExternalDiscovery::Pending(addr, bind.clone(), self.circuit_breaker);
+                // `addr` is itself the original dst, so a request that finds
+                // the endpoint not ready dials it directly.
+                (Discovery::External(pending), Some(addr))
             }
         };
 
-        let loaded = tower_balance::load::WithPendingRequests::new(resolve);
+        let breaking = CircuitBreaking::new(resolve, self.circuit_breaker);
+
+        let loaded = tower_balance::load::WithPendingRequests::new(breaking);
 
         let balance = tower_balance::power_of_two_choices(loaded, rand::thread_rng());
 
-        Buffer::new(balance, self.bind.executor())
+        // `orig_dst_fallback`'s `OrigDstFallbackError<E>` -> `BoxError`
+        // conversion only covers an already-boxed inner error, so `balance`
+        // has to be boxed before it's wrapped rather than after.
+        let balance = MapErrBoxed::new(balance);
+
+        let fallback = MapErrBoxed::new(orig_dst_fallback(balance, orig_dst, bind));
+
+        BoundedBuffer::new(fallback, self.buffer, self.bind.executor())
             .map(|buffer| {
-                let inflight = InFlightLimit::new(buffer, MAX_IN_FLIGHT);
-                let timeout = self.timeout.apply(inflight);
-                LogErrors::new(timeout)
+                let buffer_metrics = buffer.metrics();
+                let buffer = MapErrBoxed::new(buffer);
+                let inflight = MapErrBoxed::new(InFlightLimit::new(buffer, MAX_IN_FLIGHT));
+                let timeout = MapErrBoxed::new(self.timeout.apply(inflight));
+                LogErrors::new(timeout, buffer_metrics)
             })
-            .map_err(|_| {})
     }
 }
 
 pub enum Discovery<B> {
-    LocalSvc(discovery::Watch<BindProtocol<B>>),
-    External(Option<(SocketAddr, BindProtocol<B>)>),
+    LocalSvc(LocalSvcDiscovery<B>),
+    Dns(DnsDiscovery<B>),
+    External(ExternalDiscovery<B>),
+}
+
+/// Discovery state for an `External` destination: a single address that's
+/// never replaced by a later update, so unlike `LocalSvcDiscovery` and
+/// `DnsDiscovery` there's no reconciliation to do -- just the one-time
+/// insert, and the eventual removal if `Rebind` ever gives up on it for
+/// good.
+enum ExternalDiscovery<B> {
+    Pending(SocketAddr, BindProtocol<B>, CircuitBreakerConfig),
+    Bound(SocketAddr, Evicted),
+    Evicted,
 }
 
 impl<B> Discover for Discovery<B>
@@ -162,71 +266,525 @@ where
     type Request = http::Request<B>;
     type Response = bind::HttpResponse;
     type Error = <bind::Service<B> as tower::Service>::Error;
-    type Service = bind::Service<B>;
+    type Service = Rebind<B>;
     type DiscoverError = ();
 
     fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
         match *self {
-            Discovery::LocalSvc(ref mut w) => w.poll(),
-            Discovery::External(ref mut opt) => {
+            Discovery::LocalSvc(ref mut local) => local.poll(),
+            Discovery::Dns(ref mut dns) => dns.poll(),
+            Discovery::External(ref mut state) => {
                 // This "discovers" a single address for an external service
-                // that never has another change. This can mean it floats
-                // in the Balancer forever. However, when we finally add
-                // circuit-breaking, this should be able to take care of itself,
-                // closing down when the connection is no longer usable.
-                if let Some((addr, bind)) = opt.take() {
-                    let svc = bind.bind(&addr)?;
-                    Ok(Async::Ready(Change::Insert(addr, svc)))
-                } else {
-                    Ok(Async::NotReady)
+                // that never has another change (short of eviction), so it
+                // floats in the Balancer forever as long as it stays
+                // connectable. `Rebind` keeps a transient connect error from
+                // tearing down the whole `Balance`, and the outer
+                // `CircuitBreaking` wrapper handles pulling it out of P2C
+                // selection if it keeps failing; only once `Rebind` gives up
+                // for good does this evict it with a `Change::Remove`.
+                match *state {
+                    ExternalDiscovery::Pending(addr, ref bind, circuit_breaker) => {
+                        let svc = Rebind::new(addr, bind.clone(), circuit_breaker)?;
+                        let evicted = svc.evicted_handle();
+                        *state = ExternalDiscovery::Bound(addr, evicted);
+                        Ok(Async::Ready(Change::Insert(addr, svc)))
+                    }
+                    ExternalDiscovery::Bound(addr, ref evicted) => {
+                        if evicted.get() {
+                            *state = ExternalDiscovery::Evicted;
+                            Ok(Async::Ready(Change::Remove(addr)))
+                        } else {
+                            Ok(Async::NotReady)
+                        }
+                    }
+                    ExternalDiscovery::Evicted => Ok(Async::NotReady),
                 }
             }
         }
     }
 }
 
-// ===== impl LogErrors
+/// Returns the subset of `known` absent from `fresh`, for reconciling a
+/// discovery snapshot against the retained endpoint set. Shared by
+/// `LocalSvcDiscovery` and `DnsDiscovery`'s `reconcile`, both of which
+/// must queue these as `Change::Remove`s *after* the fresh set's
+/// inserts, so a snapshot that rotates the endpoint set is never
+/// observed as a transient empty set.
+fn stale_addrs(known: &HashSet<SocketAddr>, fresh: &HashSet<SocketAddr>) -> Vec<SocketAddr> {
+    known.iter().cloned().filter(|addr| !fresh.contains(addr)).collect()
+}
 
-/// Log errors talking to the controller in human format.
-pub
-struct LogErrors<S> {
-    inner: S,
+// ===== impl LocalSvcDiscovery =====
+
+const INITIAL_RESOLVE_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RESOLVE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Discovers endpoints for a local service by driving
+/// `control::discovery::Resolve`, the `Service` that resolves an
+/// authority to a stream of batched endpoint `Update`s.
+///
+/// Each `Update` is reconciled against a retained set of known endpoint
+/// addresses and turned into a queue of `Change`s to hand back one at a
+/// time to `Balance`, inserts ahead of removes, so a single update is
+/// never observed as a transient empty set. If the resolution stream
+/// ends or errors -- the controller restarted, the gRPC stream reset --
+/// the retained endpoints are left in place and kept serving traffic;
+/// resolution is retried with an exponentially growing backoff, and the
+/// first update from the new stream is treated as a full snapshot,
+/// reconciled against what's retained rather than applied as a delta.
+pub struct LocalSvcDiscovery<B> {
+    authority: FullyQualifiedAuthority,
+    control: control::Control,
+    bind: BindProtocol<B>,
+    circuit_breaker: CircuitBreakerConfig,
+    resolution: Resolving<B>,
+    known: HashSet<SocketAddr>,
+    /// An `Evicted` handle per known endpoint, checked each `poll` so an
+    /// endpoint `Rebind` has given up on for good is removed even though
+    /// discovery itself hasn't said anything about it.
+    evictions: HashMap<SocketAddr, Evicted>,
+    pending: VecDeque<Change<SocketAddr, Rebind<B>>>,
+    backoff: Duration,
 }
 
-// We want some friendly logs, but the stack of services don't have fmt::Display
-// errors, so we have to build that ourselves. For now, this hard codes the
-// expected error stack, and so any new middleware added will need to adjust this.
-//
-// The dead_code allowance is because rustc is being stupid and doesn't see it
-// is used down below.
-// #[allow(dead_code)]
-type LogError = TimeoutError<InFlightLimitError<BufferError<tower_balance::Error<ReconnectError<tower_h2::client::Error, tower_h2::client::ConnectError<TimeoutError<io::Error>>>, ()>>>>;
+enum Resolving<B> {
+    /// A resolution stream is open. `reconciled` is `false` until its
+    /// first update -- a full snapshot -- has been diffed against
+    /// `known`; later updates on the same stream are applied as deltas.
+    Active { updates: discovery::Resolution<BindProtocol<B>>, reconciled: bool },
+    /// The previous resolution ended or errored; waiting out a backoff
+    /// before reconnecting. `known` is untouched in the meantime.
+    Reconnecting(Delay),
+}
 
-impl<S> LogErrors<S>
+impl<B> LocalSvcDiscovery<B>
 where
-    S: Service<Error=LogError>,
+    B: tower_h2::Body + 'static,
 {
-    fn new(service: S) -> Self {
-        LogErrors {
-            inner: service,
+    fn new(
+        authority: FullyQualifiedAuthority,
+        control: control::Control,
+        bind: BindProtocol<B>,
+        circuit_breaker: CircuitBreakerConfig,
+    ) -> Self {
+        let updates = control.resolve(&authority, bind.clone());
+        LocalSvcDiscovery {
+            authority,
+            control,
+            bind,
+            circuit_breaker,
+            resolution: Resolving::Active { updates, reconciled: false },
+            known: HashSet::new(),
+            evictions: HashMap::new(),
+            pending: VecDeque::new(),
+            backoff: INITIAL_RESOLVE_BACKOFF,
+        }
+    }
+
+    /// Evicts any known endpoint that `Rebind` has given up on for good,
+    /// queuing a `Change::Remove` for each.
+    fn check_evictions(&mut self) {
+        let dead: Vec<SocketAddr> = self.evictions
+            .iter()
+            .filter(|&(_, evicted)| evicted.get())
+            .map(|(&addr, _)| addr)
+            .collect();
+        for addr in dead {
+            self.evictions.remove(&addr);
+            self.known.remove(&addr);
+            self.pending.push_back(Change::Remove(addr));
+        }
+    }
+
+    /// Reconciles one `Update` against `known`, queuing the `Change`s
+    /// `Balance` needs to see to reach the new state. `snapshot` is
+    /// `true` for the first update after a (re)connect, in which case
+    /// any retained endpoint absent from the update's `add` list is
+    /// queued for removal; later, incremental updates are trusted to
+    /// carry their own `remove` list instead.
+    fn reconcile(&mut self, update: discovery::Update<SocketAddr, bind::Service<B>>, snapshot: bool) {
+        let discovery::Update { add, remove } = update;
+
+        let stale: Vec<SocketAddr> = if snapshot {
+            let fresh: HashSet<SocketAddr> = add.iter().map(|&(addr, _)| addr).collect();
+            stale_addrs(&self.known, &fresh)
+        } else {
+            Vec::new()
+        };
+
+        for (addr, svc) in add {
+            self.known.insert(addr);
+            let svc = Rebind::from_parts(addr, self.bind.clone(), svc, self.circuit_breaker);
+            self.evictions.insert(addr, svc.evicted_handle());
+            self.pending.push_back(Change::Insert(addr, svc));
+        }
+
+        for addr in stale {
+            self.known.remove(&addr);
+            self.evictions.remove(&addr);
+            self.pending.push_back(Change::Remove(addr));
+        }
+
+        for addr in remove {
+            if self.known.remove(&addr) {
+                self.evictions.remove(&addr);
+                self.pending.push_back(Change::Remove(addr));
+            }
+        }
+    }
+
+    fn poll(&mut self) -> Poll<Change<SocketAddr, Rebind<B>>, ()> {
+        self.check_evictions();
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Ok(Async::Ready(change));
+            }
+
+            match self.resolution {
+                Resolving::Reconnecting(ref mut delay) => {
+                    match delay.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        // A fired or broken timer both mean "stop waiting".
+                        Ok(Async::Ready(())) | Err(_) => {}
+                    }
+                    let updates = self.control.resolve(&self.authority, self.bind.clone());
+                    self.resolution = Resolving::Active { updates, reconciled: false };
+                }
+                Resolving::Active { ref mut updates, ref mut reconciled } => {
+                    match updates.poll() {
+                        Ok(Async::Ready(Some(update))) => {
+                            let snapshot = !*reconciled;
+                            *reconciled = true;
+                            if snapshot {
+                                // The stream is healthy again; reset the
+                                // backoff so a later flap doesn't inherit
+                                // whatever delay this one grew to.
+                                self.backoff = INITIAL_RESOLVE_BACKOFF;
+                            }
+                            self.reconcile(update, snapshot);
+                        }
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(None)) | Err(_) => {
+                            debug!(
+                                "resolution for {:?} ended, retaining {} endpoints and reconnecting in {:?}",
+                                self.authority, self.known.len(), self.backoff,
+                            );
+                            let delay = Delay::new(Instant::now() + self.backoff);
+                            self.backoff = cmp::min(self.backoff * 2, MAX_RESOLVE_BACKOFF);
+                            self.resolution = Resolving::Reconnecting(delay);
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
-impl<S> Service for LogErrors<S>
+// ===== impl DnsDiscovery =====
+
+/// Configures how a `DnsDiscovery` schedules re-queries: the record TTL
+/// returned by the resolver is used as the refresh interval, clamped to
+/// `[min_ttl, max_ttl]` so a misconfigured or absent TTL can't cause
+/// refreshes that are too eager or effectively never happen.
+#[derive(Clone, Copy, Debug)]
+pub struct DnsConfig {
+    pub min_ttl: Duration,
+    pub max_ttl: Duration,
+}
+
+/// Discovers endpoints for an external `Destination::ExternalDns` by
+/// periodically re-resolving its `Name` through `dns::Resolver` and
+/// diffing the returned A/AAAA records against the retained set of
+/// known addresses, so the same P2C `Balance` used for local services
+/// load-balances and fails over across all of a DNS name's addresses
+/// instead of pinning to whichever one the client's resolver picked.
+///
+/// A failed query leaves the retained endpoints in place -- the same
+/// "keep serving, retry with backoff" behavior `LocalSvcDiscovery` uses
+/// for controller flaps -- and retries after `min_ttl`.
+pub struct DnsDiscovery<B> {
+    name: Name,
+    resolver: dns::Resolver,
+    config: DnsConfig,
+    bind: BindProtocol<B>,
+    circuit_breaker: CircuitBreakerConfig,
+    state: DnsState,
+    known: HashSet<SocketAddr>,
+    /// An `Evicted` handle per known endpoint, checked each `poll` so an
+    /// endpoint `Rebind` has given up on for good is removed even between
+    /// DNS refreshes.
+    evictions: HashMap<SocketAddr, Evicted>,
+    pending: VecDeque<Change<SocketAddr, Rebind<B>>>,
+}
+
+enum DnsState {
+    Querying(dns::ResolveFuture),
+    Waiting(Delay),
+}
+
+impl<B> DnsDiscovery<B>
 where
-    S: Service<Error=LogError>,
+    B: tower_h2::Body + 'static,
 {
-    type Request = S::Request;
-    type Response = S::Response;
-    type Error = S::Error;
-    type Future = S::Future;
+    fn new(
+        name: Name,
+        resolver: dns::Resolver,
+        config: DnsConfig,
+        bind: BindProtocol<B>,
+        circuit_breaker: CircuitBreakerConfig,
+    ) -> Self {
+        let query = resolver.resolve(&name);
+        DnsDiscovery {
+            name,
+            resolver,
+            config,
+            bind,
+            circuit_breaker,
+            state: DnsState::Querying(query),
+            known: HashSet::new(),
+            evictions: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Evicts any known endpoint that `Rebind` has given up on for good,
+    /// queuing a `Change::Remove` for each.
+    fn check_evictions(&mut self) {
+        let dead: Vec<SocketAddr> = self.evictions
+            .iter()
+            .filter(|&(_, evicted)| evicted.get())
+            .map(|(&addr, _)| addr)
+            .collect();
+        for addr in dead {
+            self.evictions.remove(&addr);
+            self.known.remove(&addr);
+            self.pending.push_back(Change::Remove(addr));
+        }
+    }
+
+    fn reconcile(&mut self, addrs: Vec<SocketAddr>) {
+        let fresh: HashSet<SocketAddr> = addrs.iter().cloned().collect();
+        let stale = stale_addrs(&self.known, &fresh);
+
+        for addr in addrs {
+            if !self.known.insert(addr) {
+                continue;
+            }
+            match Rebind::new(addr, self.bind.clone(), self.circuit_breaker) {
+                Ok(svc) => {
+                    self.evictions.insert(addr, svc.evicted_handle());
+                    self.pending.push_back(Change::Insert(addr, svc));
+                }
+                Err(_) => {
+                    debug!("failed to bind resolved address {} for {}", addr, self.name);
+                    self.known.remove(&addr);
+                }
+            }
+        }
+
+        for addr in stale {
+            self.known.remove(&addr);
+            self.evictions.remove(&addr);
+            self.pending.push_back(Change::Remove(addr));
+        }
+    }
+
+    fn poll(&mut self) -> Poll<Change<SocketAddr, Rebind<B>>, ()> {
+        self.check_evictions();
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Ok(Async::Ready(change));
+            }
+
+            match self.state {
+                DnsState::Waiting(ref mut delay) => {
+                    match delay.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        // A fired or broken timer both mean "stop waiting".
+                        Ok(Async::Ready(())) | Err(_) => {}
+                    }
+                    let query = self.resolver.resolve(&self.name);
+                    self.state = DnsState::Querying(query);
+                }
+                DnsState::Querying(ref mut query) => {
+                    match query.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(answer)) => {
+                            let refresh = cmp::max(
+                                cmp::min(answer.ttl, self.config.max_ttl),
+                                self.config.min_ttl,
+                            );
+                            self.reconcile(answer.addrs);
+                            self.state = DnsState::Waiting(Delay::new(Instant::now() + refresh));
+                        }
+                        Err(_) => {
+                            debug!(
+                                "DNS resolution for {} failed, retaining {} endpoints and retrying in {:?}",
+                                self.name, self.known.len(), self.config.min_ttl,
+                            );
+                            self.state = DnsState::Waiting(Delay::new(Instant::now() + self.config.min_ttl));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ===== impl Rebind =====
+
+/// Backoff bounds between `Rebind`'s reconnect attempts, growing the same
+/// way `LocalSvcDiscovery`/`DnsDiscovery` back off reconnecting their
+/// resolution streams, so a persistently-unreachable endpoint isn't
+/// hammered with back-to-back connect attempts.
+const INITIAL_REBIND_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_REBIND_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Shared between a `Rebind` and the `Discover` that created it. `Rebind`
+/// sets this once it gives up retrying a dead endpoint, so the `Discover`
+/// can notice on its next `poll` and evict the endpoint with a
+/// `Change::Remove` instead of leaving a permanently `NotReady` service
+/// parked in the balancer forever.
+#[derive(Clone, Default)]
+struct Evicted(Arc<AtomicBool>);
+
+impl Evicted {
+    fn set(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Wraps a single endpoint's `bind::Service` so that a connect error
+/// doesn't surface as a `ReconnectError` and tear down everything queued
+/// in the outer `Buffer`.
+///
+/// Rather than propagating the error from `poll_ready`, `Rebind` retains
+/// the endpoint in the balancer, rebinds the connection, and reports
+/// `NotReady` so the caller re-polls (and the `Buffer` retries) instead
+/// of failing the in-flight and buffered requests outright -- but only
+/// for the first `CircuitBreakerConfig.consecutive_failures` consecutive
+/// connect errors, the same count `Breaker` uses as its own ejection
+/// threshold, so at least the two don't use two different magic numbers
+/// for "how many failures is too many". Past that, the endpoint is
+/// treated as terminally dead: the error is propagated instead of
+/// swallowed, and `evicted` is set so the `Discover` that created this
+/// `Rebind` evicts it on its next poll.
+///
+/// Note this means `Breaker`'s cooldown-then-half-open-probe cycle never
+/// actually runs for connect errors: every sub-threshold failure here is
+/// swallowed as `NotReady`, so `Breaker` never observes an `Err` -- and
+/// therefore never calls `record_failure` -- until the one poll where
+/// `Rebind` has already given up and is about to evict the endpoint for
+/// good. That cycle only plays out for in-flight `5xx`/stream-reset
+/// failures surfaced through `BreakerFuture`.
+pub struct Rebind<B> {
+    addr: SocketAddr,
+    bind: BindProtocol<B>,
+    inner: bind::Service<B>,
+    circuit_breaker: CircuitBreakerConfig,
+    consecutive_failures: usize,
+    evicted: Evicted,
+    /// Backoff before the next reconnect attempt is allowed to proceed;
+    /// doubles on each consecutive failure and resets on success.
+    backoff: Duration,
+    delay: Option<Delay>,
+}
+
+impl<B> Rebind<B>
+where
+    B: tower_h2::Body + 'static,
+{
+    fn new(
+        addr: SocketAddr,
+        bind: BindProtocol<B>,
+        circuit_breaker: CircuitBreakerConfig,
+    ) -> Result<Self, ()> {
+        let inner = bind.bind(&addr)?;
+        Ok(Self::from_parts(addr, bind, inner, circuit_breaker))
+    }
+
+    fn from_parts(
+        addr: SocketAddr,
+        bind: BindProtocol<B>,
+        inner: bind::Service<B>,
+        circuit_breaker: CircuitBreakerConfig,
+    ) -> Self {
+        Rebind {
+            addr,
+            bind,
+            inner,
+            circuit_breaker,
+            consecutive_failures: 0,
+            evicted: Evicted::default(),
+            backoff: INITIAL_REBIND_BACKOFF,
+            delay: None,
+        }
+    }
+
+    /// A handle a `Discover` can poll to learn when this endpoint has been
+    /// given up on for good.
+    fn evicted_handle(&self) -> Evicted {
+        self.evicted.clone()
+    }
+}
+
+impl<B> Service for Rebind<B>
+where
+    B: tower_h2::Body + 'static,
+{
+    type Request = http::Request<B>;
+    type Response = bind::HttpResponse;
+    type Error = <bind::Service<B> as Service>::Error;
+    type Future = <bind::Service<B> as Service>::Future;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
-        self.inner.poll_ready().map_err(|e| {
-            error!("bind service error: {}", HumanError(&e));
-            e
-        })
+        if let Some(ref mut delay) = self.delay {
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // A fired or broken timer both mean "stop waiting".
+                Ok(Async::Ready(())) | Err(_) => {}
+            }
+        }
+        self.delay = None;
+
+        match self.inner.poll_ready() {
+            Ok(ready) => {
+                self.consecutive_failures = 0;
+                self.backoff = INITIAL_REBIND_BACKOFF;
+                Ok(ready)
+            }
+            Err(e) => {
+                self.consecutive_failures += 1;
+                if self.consecutive_failures > self.circuit_breaker.consecutive_failures {
+                    debug!(
+                        "endpoint {} failed to connect {} times in a row, evicting",
+                        self.addr, self.consecutive_failures,
+                    );
+                    self.evicted.set();
+                    return Err(e);
+                }
+                debug!(
+                    "endpoint {} connect error, rebinding in {:?}",
+                    self.addr, self.backoff,
+                );
+                // Retained (not evicted): try a fresh connection and let
+                // the caller re-poll -- after waiting out the backoff --
+                // rather than propagating the error.
+                if let Ok(fresh) = self.bind.bind(&self.addr) {
+                    self.inner = fresh;
+                }
+                let mut delay = Delay::new(Instant::now() + self.backoff);
+                // Poll once so this task is woken when the backoff elapses.
+                let _ = delay.poll();
+                self.delay = Some(delay);
+                self.backoff = cmp::min(self.backoff * 2, MAX_REBIND_BACKOFF);
+                Ok(Async::NotReady)
+            }
+        }
     }
 
     fn call(&mut self, req: Self::Request) -> Self::Future {
@@ -234,17 +792,1359 @@ where
     }
 }
 
-struct HumanError<'a>(&'a LogError);
+// ===== impl CircuitBreaking =====
 
-impl<'a> fmt::Display for HumanError<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self.0 {
-            TimeoutError::Error(ref e) => {
-                fmt::Debug::fmt(e, f)
+/// Configures the passive circuit breaker applied to each outbound
+/// endpoint: how many consecutive failures eject an endpoint, how long
+/// the first ejection lasts, and how much of the balancer's endpoints
+/// may be ejected at once.
+#[derive(Clone, Copy, Debug)]
+pub struct CircuitBreakerConfig {
+    pub max_ejection_percent: f64,
+    pub consecutive_failures: usize,
+    pub base_ejection_time: Duration,
+}
+
+/// Wraps a `Discover` so each endpoint it produces is wrapped in a
+/// `Breaker`, and endpoints share failure/ejection bookkeeping across
+/// `Rebind` reconnects (keyed by address, for the lifetime of this
+/// balancer).
+pub struct CircuitBreaking<D> {
+    inner: D,
+    config: CircuitBreakerConfig,
+    totals: Arc<Totals>,
+    states: HashMap<SocketAddr, Arc<EndpointState>>,
+}
+
+impl<D> CircuitBreaking<D> {
+    fn new(inner: D, config: CircuitBreakerConfig) -> Self {
+        CircuitBreaking {
+            inner,
+            config,
+            totals: Arc::new(Totals::default()),
+            states: HashMap::new(),
+        }
+    }
+}
+
+impl<D> Discover for CircuitBreaking<D>
+where
+    D: Discover<Key = SocketAddr>,
+{
+    type Key = SocketAddr;
+    type Request = D::Request;
+    type Response = D::Response;
+    type Error = D::Error;
+    type Service = Breaker<D::Service>;
+    type DiscoverError = D::DiscoverError;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::DiscoverError> {
+        match self.inner.poll()? {
+            Async::Ready(Change::Insert(addr, svc)) => {
+                let state = self.states
+                    .entry(addr)
+                    .or_insert_with(|| Arc::new(EndpointState::new(self.totals.clone())))
+                    .clone();
+                let svc = Breaker::new(svc, state, self.config);
+                Ok(Async::Ready(Change::Insert(addr, svc)))
+            }
+            Async::Ready(Change::Remove(addr)) => {
+                if let Some(state) = self.states.remove(&addr) {
+                    state.evict();
+                }
+                Ok(Async::Ready(Change::Remove(addr)))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Tracks how many endpoints a `CircuitBreaking` balancer has, and how
+/// many of them are currently ejected, so ejection can be capped at
+/// `max_ejection_percent` of the pool.
+#[derive(Default)]
+struct Totals {
+    total: AtomicUsize,
+    ejected: AtomicUsize,
+}
+
+/// Per-endpoint circuit breaker state, shared between the `Breaker`
+/// service wrapping an endpoint and the `CircuitBreaking` discover that
+/// created it.
+struct EndpointState {
+    totals: Arc<Totals>,
+    consecutive_failures: AtomicUsize,
+    ejections: AtomicUsize,
+    ejected_until: Mutex<Option<Instant>>,
+    half_open: AtomicBool,
+}
+
+impl EndpointState {
+    fn new(totals: Arc<Totals>) -> Self {
+        totals.total.fetch_add(1, Ordering::Relaxed);
+        EndpointState {
+            totals,
+            consecutive_failures: AtomicUsize::new(0),
+            ejections: AtomicUsize::new(0),
+            ejected_until: Mutex::new(None),
+            half_open: AtomicBool::new(false),
+        }
+    }
+
+    fn is_ejected(&self) -> bool {
+        self.ejected_until.lock().expect("circuit breaker lock poisoned").is_some()
+            || self.half_open.load(Ordering::Relaxed)
+    }
+
+    /// Called when `Discover` removes the endpoint this state belongs
+    /// to, so `totals` stops counting it -- otherwise `total` grows
+    /// unbounded across discovery churn while `ejected` only ever
+    /// reflects the live pool, making `max_ejection_percent` progressively
+    /// more permissive than configured.
+    fn evict(&self) {
+        let was_ejected = self.ejected_until
+            .lock()
+            .expect("circuit breaker lock poisoned")
+            .take()
+            .is_some();
+        if was_ejected || self.half_open.swap(false, Ordering::Relaxed) {
+            self.totals.ejected.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.totals.total.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// If this endpoint's ejection cooldown has elapsed, admits a single
+    /// half-open probe and returns `true`. `half_open` is the admit-once
+    /// gate: only the caller that flips it from `false` to `true` is the
+    /// probe; once it's set, every other caller is rejected (via
+    /// `is_ejected` seeing `half_open` even though `ejected_until` has
+    /// been cleared) until `record_success`/`record_failure` resolves it.
+    fn ready_for_probe(&self) -> bool {
+        let mut until = self.ejected_until.lock().expect("circuit breaker lock poisoned");
+        match *until {
+            Some(at) if Instant::now() >= at => {
+                if self.half_open.compare_and_swap(false, true, Ordering::Relaxed) {
+                    // Someone else already won the race to probe.
+                    false
+                } else {
+                    *until = None;
+                    true
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn record_success(&self) {
+        let was_ejected = self.ejected_until
+            .lock()
+            .expect("circuit breaker lock poisoned")
+            .take()
+            .is_some();
+        if was_ejected || self.half_open.swap(false, Ordering::Relaxed) {
+            self.totals.ejected.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        self.ejections.store(0, Ordering::Relaxed);
+    }
+
+    fn record_failure(&self, config: &CircuitBreakerConfig) {
+        let was_half_open = self.half_open.swap(false, Ordering::Relaxed);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if !was_half_open && failures < config.consecutive_failures {
+            return;
+        }
+
+        // A half-open or already-ejected endpoint is already counted in
+        // `totals.ejected`; only a fresh ejection adds to it.
+        let already_ejected = self.ejected_until
+            .lock()
+            .expect("circuit breaker lock poisoned")
+            .is_some();
+        if !was_half_open && !already_ejected {
+            let total = self.totals.total.load(Ordering::Relaxed).max(1) as f64;
+            let ejected = self.totals.ejected.load(Ordering::Relaxed) as f64;
+            if (ejected + 1.0) / total > config.max_ejection_percent {
+                // Ejecting this endpoint would exceed the configured cap
+                // on how much of the pool may be down at once; leave it
+                // in rotation rather than risk starving the balancer.
+                return;
+            }
+            self.totals.ejected.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let ejection = self.ejections.fetch_add(1, Ordering::Relaxed) as u32;
+        let backoff = config.base_ejection_time * 2u32.pow(ejection.min(6));
+        *self.ejected_until.lock().expect("circuit breaker lock poisoned") = Some(Instant::now() + backoff);
+    }
+}
+
+/// Ejects an endpoint from P2C selection for a cooldown window after it
+/// accumulates `consecutive_failures` in-flight `5xx`/stream-reset
+/// failures (observed via `BreakerFuture`), then admits it back in a
+/// half-open state that probes with a single request, closing the
+/// breaker on success and re-ejecting (with a longer cooldown) on
+/// failure.
+///
+/// Ejection is implemented the same way `Rebind` hides an endpoint with
+/// a fresh connect error: by reporting `NotReady` from `poll_ready`, which
+/// is all `Balance`'s P2C needs to skip this endpoint -- there's no need
+/// to round-trip through `Discover`'s `Change::Remove`/`Insert`.
+///
+/// Connect errors are also wired into `record_failure` via the `Err` arm
+/// below, but in practice never reach it until `Rebind`'s own retry
+/// budget (see its doc comment) is exhausted and it's already evicting
+/// the endpoint outright -- so this cooldown/probe cycle is, for now,
+/// effectively only exercised by in-flight response failures, not
+/// connect errors.
+pub struct Breaker<S> {
+    inner: S,
+    state: Arc<EndpointState>,
+    config: CircuitBreakerConfig,
+}
+
+impl<S> Breaker<S> {
+    fn new(inner: S, state: Arc<EndpointState>, config: CircuitBreakerConfig) -> Self {
+        Breaker { inner, state, config }
+    }
+}
+
+impl<S, B> Service for Breaker<S>
+where
+    S: Service<Request = http::Request<B>, Response = bind::HttpResponse>,
+    B: tower_h2::Body + 'static,
+{
+    type Request = http::Request<B>;
+    type Response = bind::HttpResponse;
+    type Error = S::Error;
+    type Future = BreakerFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if self.state.is_ejected() && !self.state.ready_for_probe() {
+            return Ok(Async::NotReady);
+        }
+        match self.inner.poll_ready() {
+            Ok(ready) => Ok(ready),
+            Err(e) => {
+                self.state.record_failure(&self.config);
+                Err(e)
+            }
+        }
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        BreakerFuture {
+            inner: self.inner.call(req),
+            state: self.state.clone(),
+            config: self.config,
+        }
+    }
+}
+
+pub struct BreakerFuture<F> {
+    inner: F,
+    state: Arc<EndpointState>,
+    config: CircuitBreakerConfig,
+}
+
+impl<F> Future for BreakerFuture<F>
+where
+    F: Future<Item = bind::HttpResponse>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::Ready(rsp)) => {
+                if rsp.status().is_server_error() {
+                    self.state.record_failure(&self.config);
+                } else {
+                    self.state.record_success();
+                }
+                Ok(Async::Ready(rsp))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.state.record_failure(&self.config);
+                Err(e)
+            }
+        }
+    }
+}
+
+// ===== impl ReplayBody =====
+
+/// Wraps a `tower_h2::Body` so every chunk read through it is also
+/// captured into a buffer shared (via `Arc`) with a second handle, so
+/// that second handle can replay the request from the start regardless
+/// of how much of the original body the first handle had already read.
+///
+/// This is what lets `Fallback` support a `primary` that starts reading
+/// the request body before it decides to fall back: the body handed to
+/// `primary` is a `ReplayBody`, and if `primary` bails out, `Fallback`
+/// hands `fallback` a handle that replays whatever `primary` had already
+/// captured and then keeps streaming the live body from where `primary`
+/// left off (still capturing, in case of a second fallback downstream),
+/// so `fallback` can start forwarding bytes immediately instead of
+/// waiting for the whole request to buffer first.
+pub struct ReplayBody<B> {
+    state: ReplayState<B>,
+    buffered: Arc<Mutex<Vec<Bytes>>>,
+}
+
+enum ReplayState<B> {
+    /// Streaming the live body; each chunk read is appended to `buffered`
+    /// as it's returned.
+    Live(B),
+    /// Replaying previously captured chunks starting from `buffered[0]`;
+    /// once caught up to `buffered`'s end, switches to streaming (and
+    /// still capturing) the live body rather than ending the stream
+    /// there.
+    Tail(usize, B),
+}
+
+impl<B: tower_h2::Body> ReplayBody<B> {
+    fn new(inner: B) -> Self {
+        ReplayBody {
+            state: ReplayState::Live(inner),
+            buffered: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Converts this handle into one that replays whatever it had
+    /// captured so far from the start, then continues streaming (and
+    /// capturing) the live body where this handle left off. The result
+    /// can be handed off immediately -- it doesn't require the live body
+    /// to have been drained to its end first.
+    fn replay_from_here(self) -> Self {
+        match self.state {
+            ReplayState::Live(inner) => ReplayBody {
+                state: ReplayState::Tail(0, inner),
+                buffered: self.buffered,
             },
-            TimeoutError::Timeout(ref after) => {
-               write!(f, "binding timed out after {:?}", after)
+            // `Fallback` only ever calls this on the body handed back by
+            // a `primary` that errored out of its own `poll`/`call`, which
+            // is always still `Live`.
+            ReplayState::Tail(_, _) => {
+                unreachable!("replay_from_here called on an already-replaying body")
+            }
+        }
+    }
+}
+
+impl<B> tower_h2::Body for ReplayBody<B>
+where
+    B: tower_h2::Body,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.state {
+            ReplayState::Live(ref inner) => inner.is_end_stream(),
+            ReplayState::Tail(pos, ref inner) => {
+                pos >= self.buffered.lock().expect("replay buffer lock poisoned").len()
+                    && inner.is_end_stream()
+            }
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.state {
+            ReplayState::Live(ref mut inner) => match inner.poll_data() {
+                Ok(Async::Ready(Some(data))) => {
+                    let mut buf = data.into_buf();
+                    let mut bytes = BytesMut::with_capacity(buf.remaining());
+                    bytes.put(&mut buf);
+                    let bytes = bytes.freeze();
+                    self.buffered
+                        .lock()
+                        .expect("replay buffer lock poisoned")
+                        .push(Bytes::clone(&bytes));
+                    Ok(Async::Ready(Some(bytes)))
+                }
+                other => other.map(|async_| async_.map(|_| None)),
             },
+            ReplayState::Tail(ref mut pos, ref mut inner) => {
+                let captured = self.buffered
+                    .lock()
+                    .expect("replay buffer lock poisoned")
+                    .get(*pos)
+                    .cloned();
+                if let Some(chunk) = captured {
+                    *pos += 1;
+                    return Ok(Async::Ready(Some(chunk)));
+                }
+                match inner.poll_data() {
+                    Ok(Async::Ready(Some(data))) => {
+                        let mut buf = data.into_buf();
+                        let mut bytes = BytesMut::with_capacity(buf.remaining());
+                        bytes.put(&mut buf);
+                        let bytes = bytes.freeze();
+                        self.buffered
+                            .lock()
+                            .expect("replay buffer lock poisoned")
+                            .push(Bytes::clone(&bytes));
+                        *pos += 1;
+                        Ok(Async::Ready(Some(bytes)))
+                    }
+                    other => other.map(|async_| async_.map(|_| None)),
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match self.state {
+            ReplayState::Live(ref mut inner) => inner.poll_trailers(),
+            // Trailers aren't captured for replay: by the time a request
+            // falls back this far, it's effectively a last resort, and
+            // the original destination dial below never reads trailers
+            // off outbound requests anyway.
+            ReplayState::Tail(_, _) => Ok(Async::Ready(None)),
         }
     }
 }
+
+// ===== impl Fallback =====
+
+/// Composes a `primary` and `fallback` `Service`, both over
+/// `http::Request<ReplayBody<B>>`, from a plain `http::Request<B>`.
+///
+/// The incoming request's body is wrapped in a `ReplayBody` before
+/// `primary` ever sees it. If `primary`'s future resolves to
+/// `Error::Fallback`, the request is immediately replayed against
+/// `fallback`: whatever `primary` had already read is replayed from the
+/// start, and the rest streams straight from the live body as it
+/// arrives, so `fallback` doesn't wait on the client to finish sending
+/// before it can start forwarding. Any other error is propagated
+/// unchanged. This lets the ORIG_DST fallback below -- and future
+/// fallback-shaped features -- be expressed declaratively instead of
+/// each hand-rolling its own "try this, then that" state machine, and it
+/// works regardless of how much of the body `primary` had already
+/// consumed before giving up.
+pub struct Fallback<P, F> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P, F> Fallback<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Fallback { primary, fallback }
+    }
+}
+
+impl<P, F, B, E, PB, FB> Service for Fallback<P, F>
+where
+    B: tower_h2::Body + 'static,
+    P: Service<Request = http::Request<ReplayBody<B>>, Response = http::Response<PB>, Error = Error<ReplayBody<B>, E>>,
+    F: Service<Request = http::Request<ReplayBody<B>>, Response = http::Response<FB>, Error = E> + Clone,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<Either<PB, FB>>;
+    type Error = E;
+    type Future = ResponseFuture<P::Future, F, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self.primary.poll_ready() {
+            Ok(ready) => Ok(ready),
+            Err(Error::Inner(e)) => Err(e),
+            Err(Error::Fallback(_)) => {
+                unreachable!("poll_ready must not produce a request to replay")
+            }
+        }
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let req = req.map(ReplayBody::new);
+        ResponseFuture::Primary(self.primary.call(req), self.fallback.clone(), ::std::marker::PhantomData)
+    }
+}
+
+/// The `primary`-then-`fallback` state machine driving `Fallback`'s response.
+pub enum ResponseFuture<PF, F, B>
+where
+    F: Service,
+{
+    Primary(PF, F, ::std::marker::PhantomData<fn(B)>),
+    Fallback(F::Future),
+}
+
+impl<PF, F, B, E, PB, FB> Future for ResponseFuture<PF, F, B>
+where
+    B: tower_h2::Body + 'static,
+    PF: Future<Item = http::Response<PB>, Error = Error<ReplayBody<B>, E>>,
+    F: Service<Request = http::Request<ReplayBody<B>>, Response = http::Response<FB>, Error = E>,
+{
+    type Item = http::Response<Either<PB, FB>>;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match *self {
+                ResponseFuture::Primary(ref mut fut, ref mut fallback, _) => match fut.poll() {
+                    Ok(Async::Ready(rsp)) => return Ok(Async::Ready(rsp.map(Either::A))),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(Error::Inner(e)) => return Err(e),
+                    Err(Error::Fallback(req)) => {
+                        let replay = req.map(ReplayBody::replay_from_here);
+                        ResponseFuture::Fallback(fallback.call(replay))
+                    }
+                },
+                ResponseFuture::Fallback(ref mut fut) => {
+                    return fut.poll().map(|async_rsp| async_rsp.map(|rsp| rsp.map(Either::B)));
+                }
+            };
+            *self = next;
+        }
+    }
+}
+
+/// An error that lets a `Fallback` primary hand an unconsumed request back
+/// out to be replayed against the fallback service, while still
+/// propagating any other error unchanged.
+pub enum Error<B, E> {
+    Fallback(http::Request<B>),
+    Inner(E),
+}
+
+impl<B, E: fmt::Debug> fmt::Debug for Error<B, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Fallback(_) => f.write_str("Error::Fallback(..)"),
+            Error::Inner(ref e) => write!(f, "Error::Inner({:?})", e),
+        }
+    }
+}
+
+// ===== impl OrigDstFallback =====
+
+/// Falls back to dialing the connection's original destination directly
+/// whenever the outbound balancer has no ready endpoints.
+///
+/// This keeps a `LocalSvc` destination serving traffic through discovery
+/// churn or controller outages, rather than letting requests buffer up
+/// until the outer `Timeout` fires. Once the balancer reports ready
+/// endpoints again, traffic goes back to being load balanced.
+pub type OrigDstFallback<S, B> = Fallback<NoEndpoints<S>, Dial<ReplayBody<B>, <S as Service>::Error>>;
+
+fn orig_dst_fallback<S, B>(
+    balance: S,
+    orig_dst: Option<SocketAddr>,
+    bind: BindProtocol<ReplayBody<B>>,
+) -> OrigDstFallback<S, B>
+where
+    S: Service<Request = http::Request<ReplayBody<B>>, Response = bind::HttpResponse>,
+    B: tower_h2::Body + 'static,
+{
+    Fallback::new(NoEndpoints::new(balance), Dial::new(bind, orig_dst))
+}
+
+/// Adapts a load-balancing `Service` so that, instead of sitting
+/// `NotReady` forever when it has no ready endpoints, it reports itself
+/// ready and hands back any request it can't serve as `Error::Fallback`,
+/// for a `Fallback` to replay against a fallback service.
+pub struct NoEndpoints<S> {
+    balance: S,
+    ready: bool,
+}
+
+impl<S> NoEndpoints<S> {
+    fn new(balance: S) -> Self {
+        NoEndpoints {
+            balance,
+            ready: false,
+        }
+    }
+}
+
+impl<S, B> Service for NoEndpoints<S>
+where
+    S: Service<Request = http::Request<B>>,
+    B: tower_h2::Body + 'static,
+{
+    type Request = http::Request<B>;
+    type Response = S::Response;
+    type Error = Error<B, OrigDstFallbackError<S::Error>>;
+    type Future = NoEndpointsFuture<S::Future, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self.balance.poll_ready() {
+            Ok(Async::Ready(())) => {
+                self.ready = true;
+                Ok(Async::Ready(()))
+            }
+            Ok(Async::NotReady) => {
+                // No endpoints are ready yet; report ready anyway so
+                // `call` hands the request back out to fall back on,
+                // instead of buffering it here indefinitely.
+                self.ready = false;
+                Ok(Async::Ready(()))
+            }
+            Err(e) => Err(Error::Inner(OrigDstFallbackError::Balance(e))),
+        }
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        if self.ready {
+            NoEndpointsFuture::Balance(self.balance.call(req))
+        } else {
+            NoEndpointsFuture::Fallback(Some(req))
+        }
+    }
+}
+
+pub enum NoEndpointsFuture<F, B> {
+    Balance(F),
+    Fallback(Option<http::Request<B>>),
+}
+
+impl<F, B> Future for NoEndpointsFuture<F, B>
+where
+    F: Future,
+{
+    type Item = F::Item;
+    type Error = Error<B, OrigDstFallbackError<F::Error>>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            NoEndpointsFuture::Balance(ref mut f) => f
+                .poll()
+                .map_err(|e| Error::Inner(OrigDstFallbackError::Balance(e))),
+            NoEndpointsFuture::Fallback(ref mut req) => {
+                let req = req.take().expect("polled after completion");
+                Err(Error::Fallback(req))
+            }
+        }
+    }
+}
+
+/// The fallback target for `OrigDstFallback`: dials the connection's
+/// original destination directly, bypassing service discovery.
+pub struct Dial<B, E> {
+    bind: BindProtocol<B>,
+    orig_dst: Option<SocketAddr>,
+    _marker: ::std::marker::PhantomData<fn() -> E>,
+}
+
+impl<B, E> Dial<B, E> {
+    fn new(bind: BindProtocol<B>, orig_dst: Option<SocketAddr>) -> Self {
+        Dial {
+            bind,
+            orig_dst,
+            _marker: ::std::marker::PhantomData,
+        }
+    }
+}
+
+impl<B, E> Clone for Dial<B, E> {
+    fn clone(&self) -> Self {
+        Dial {
+            bind: self.bind.clone(),
+            orig_dst: self.orig_dst,
+            _marker: ::std::marker::PhantomData,
+        }
+    }
+}
+
+impl<B, E> Service for Dial<B, E>
+where
+    B: tower_h2::Body + 'static,
+{
+    type Request = http::Request<B>;
+    type Response = bind::HttpResponse;
+    type Error = OrigDstFallbackError<E>;
+    type Future = DialFuture<B, E>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let addr = match self.orig_dst {
+            Some(addr) => addr,
+            None => return DialFuture::Failed(Some(OrigDstFallbackError::NoOrigDst)),
+        };
+        match self.bind.bind(&addr) {
+            Ok(mut svc) => DialFuture::Connecting(svc.call(req)),
+            Err(()) => DialFuture::Failed(Some(OrigDstFallbackError::Connect(()))),
+        }
+    }
+}
+
+pub enum DialFuture<B, E>
+where
+    B: tower_h2::Body + 'static,
+{
+    Connecting(<bind::Service<B> as Service>::Future),
+    Failed(Option<OrigDstFallbackError<E>>),
+}
+
+impl<B, E> Future for DialFuture<B, E>
+where
+    B: tower_h2::Body + 'static,
+{
+    type Item = bind::HttpResponse;
+    type Error = OrigDstFallbackError<E>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            DialFuture::Connecting(ref mut f) => {
+                f.poll().map_err(|_| OrigDstFallbackError::Connect(()))
+            }
+            DialFuture::Failed(ref mut e) => Err(e.take().expect("polled after completion")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OrigDstFallbackError<E> {
+    Balance(E),
+    NoOrigDst,
+    Connect(()),
+}
+
+// ===== impl BoundedBuffer =====
+
+/// Configures the bounded buffer placed in front of each outbound
+/// destination's `Balance`: how many requests may queue waiting for it
+/// to become ready, and how long one of them may wait before it's
+/// dropped with a timeout rather than dispatched.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferConfig {
+    pub capacity: usize,
+    pub max_wait: Duration,
+}
+
+/// A bounded, load-shedding alternative to an unbounded `tower_buffer::Buffer`.
+///
+/// Requests are handed off to a `Worker` running on the `Bind`'s executor,
+/// which owns the wrapped service and is driven independently of any
+/// particular caller -- the same reason `LocalSvcDiscovery`'s resolution
+/// has to be polled even between calls, so `Balance` keeps observing
+/// `Discover` updates. `poll_ready` never waits: once `capacity` requests
+/// are already queued for the `Worker`, it reports `Overloaded` instead
+/// of backing up further, and a request that's waited longer than
+/// `max_wait` for the `Worker` to reach it is dropped from the queue
+/// with `TimedOut` before it's ever dispatched to the wrapped service.
+pub struct BoundedBuffer<S: Service> {
+    tx: mpsc::Sender<Message<S>>,
+    max_wait: Duration,
+    metrics: Arc<BufferMetrics>,
+}
+
+impl<S: Service> Clone for BoundedBuffer<S> {
+    fn clone(&self) -> Self {
+        BoundedBuffer {
+            tx: self.tx.clone(),
+            max_wait: self.max_wait,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<S> BoundedBuffer<S>
+where
+    S: Service + Send + 'static,
+    S::Request: Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    /// Spawns a `Worker` driving `inner` onto `executor`, returning the
+    /// bounded front-end handle that queues requests to it.
+    fn new<E>(inner: S, config: BufferConfig, executor: &E) -> Result<Self, ()>
+    where
+        E: Executor<Worker<S>>,
+    {
+        // `mpsc::channel(n)` holds `n` messages plus one per sender, so
+        // with the single handle made here it holds `capacity`.
+        let (tx, rx) = mpsc::channel(config.capacity.saturating_sub(1));
+        let metrics = Arc::new(BufferMetrics::default());
+        let worker = Worker {
+            inner,
+            rx,
+            pending: None,
+            deadline_timer: None,
+            metrics: metrics.clone(),
+        };
+        executor.execute(worker).map_err(|_| ())?;
+        Ok(BoundedBuffer { tx, max_wait: config.max_wait, metrics })
+    }
+
+    /// A handle onto the counts of requests this buffer has shed or
+    /// expired, for the metrics path to report alongside everything else
+    /// in the stack. Cloned out rather than borrowed, since callers need
+    /// to hold onto it after this `BoundedBuffer` itself is moved into
+    /// further wrapper layers.
+    pub fn metrics(&self) -> Arc<BufferMetrics> {
+        self.metrics.clone()
+    }
+}
+
+impl<S: Service> Service for BoundedBuffer<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = BoundedBufferError<S::Error>;
+    type Future = BoundedBufferFuture<S::Future, S::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self.tx.poll_ready() {
+            Ok(Async::Ready(())) => Ok(Async::Ready(())),
+            Ok(Async::NotReady) => {
+                self.metrics.shed.fetch_add(1, Ordering::Relaxed);
+                Err(BoundedBufferError::Overloaded)
+            }
+            Err(_) => Err(BoundedBufferError::WorkerGone),
+        }
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let (reply, rx) = oneshot::channel();
+        let deadline = Instant::now() + self.max_wait;
+        match self.tx.try_send(Message { request, deadline, reply }) {
+            Ok(()) => BoundedBufferFuture::Queued(rx),
+            Err(e) => {
+                let err = if e.is_disconnected() {
+                    BoundedBufferError::WorkerGone
+                } else {
+                    self.metrics.shed.fetch_add(1, Ordering::Relaxed);
+                    BoundedBufferError::Overloaded
+                };
+                BoundedBufferFuture::Done(Some(err))
+            }
+        }
+    }
+}
+
+/// Counts requests a `BoundedBuffer` has dropped rather than dispatched,
+/// for the metrics/log path to surface alongside `CircuitBreaking`'s
+/// `Totals`.
+#[derive(Default)]
+pub struct BufferMetrics {
+    shed: AtomicUsize,
+    expired: AtomicUsize,
+}
+
+impl BufferMetrics {
+    pub fn shed_total(&self) -> usize {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    pub fn expired_total(&self) -> usize {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+struct Message<S: Service> {
+    request: S::Request,
+    deadline: Instant,
+    reply: oneshot::Sender<Result<S::Future, BoundedBufferError<S::Error>>>,
+}
+
+/// Drives the `Service` wrapped by a `BoundedBuffer`. Holds at most one
+/// message out of the channel at a time: `pending` is checked against
+/// its `deadline` before the result of `inner.poll_ready()` is acted on,
+/// so a request that's already expired is dropped whether or not the
+/// wrapped service is ready, and `deadline_timer` guarantees the worker wakes to
+/// make that check even if `inner` never becomes ready on its own.
+pub struct Worker<S: Service> {
+    inner: S,
+    rx: mpsc::Receiver<Message<S>>,
+    pending: Option<Message<S>>,
+    deadline_timer: Option<Delay>,
+    metrics: Arc<BufferMetrics>,
+}
+
+impl<S: Service> Future for Worker<S> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            // Polled every iteration, whether or not a message is
+            // pending, so the wrapped `Balance`/`Discovery` stack keeps
+            // making background progress (resolution-stream reconnects,
+            // DNS re-queries, `Rebind` backoff and evicted-endpoint
+            // cleanup) even while this destination has nothing queued,
+            // instead of stalling until the next request happens to
+            // arrive.
+            let ready = self.inner.poll_ready();
+
+            if self.pending.is_none() {
+                match self.rx.poll() {
+                    Ok(Async::Ready(Some(msg))) => {
+                        self.deadline_timer = Some(Delay::new(msg.deadline));
+                        self.pending = Some(msg);
+                    }
+                    // All `BoundedBuffer` handles have been dropped; nothing
+                    // left to drive.
+                    Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(()) => return Ok(Async::Ready(())),
+                }
+            }
+
+            if Instant::now() >= self.pending.as_ref().expect("just set").deadline {
+                let msg = self.pending.take().expect("just set");
+                self.deadline_timer = None;
+                self.metrics.expired.fetch_add(1, Ordering::Relaxed);
+                let _ = msg.reply.send(Err(BoundedBufferError::TimedOut));
+                continue;
+            }
+
+            match ready {
+                Ok(Async::Ready(())) => {
+                    let msg = self.pending.take().expect("just set");
+                    self.deadline_timer = None;
+                    let fut = self.inner.call(msg.request);
+                    let _ = msg.reply.send(Ok(fut));
+                }
+                Ok(Async::NotReady) => {
+                    match self.deadline_timer.as_mut().expect("set above").poll() {
+                        Ok(Async::Ready(())) | Err(_) => continue,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    }
+                }
+                Err(e) => {
+                    let msg = self.pending.take().expect("just set");
+                    self.deadline_timer = None;
+                    let _ = msg.reply.send(Err(BoundedBufferError::Inner(e)));
+                }
+            }
+        }
+    }
+}
+
+/// The error type of a `BoundedBuffer`: either the wrapped service
+/// itself failed, or the request never reached it -- shed for arriving
+/// when the queue was already at capacity, dropped for waiting past its
+/// deadline in the queue, or abandoned because the `Worker` driving the
+/// wrapped service ended.
+#[derive(Debug)]
+pub enum BoundedBufferError<E> {
+    Inner(E),
+    Overloaded,
+    TimedOut,
+    WorkerGone,
+}
+
+impl<E: fmt::Display> fmt::Display for BoundedBufferError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BoundedBufferError::Inner(ref e) => fmt::Display::fmt(e, f),
+            BoundedBufferError::Overloaded => f.write_str("request queue is full"),
+            BoundedBufferError::TimedOut => f.write_str("timed out waiting in the request queue"),
+            BoundedBufferError::WorkerGone => f.write_str("buffer worker terminated"),
+        }
+    }
+}
+
+impl<E> ::std::error::Error for BoundedBufferError<E>
+where
+    E: ::std::error::Error + 'static,
+{
+    fn description(&self) -> &str {
+        match *self {
+            BoundedBufferError::Inner(ref e) => e.description(),
+            BoundedBufferError::Overloaded => "request queue is full",
+            BoundedBufferError::TimedOut => "timed out waiting in the request queue",
+            BoundedBufferError::WorkerGone => "buffer worker terminated",
+        }
+    }
+
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
+        match *self {
+            BoundedBufferError::Inner(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The `Future` returned by `BoundedBuffer::call`: waits for the
+/// `Worker` to either dispatch the request to the wrapped service (in
+/// which case this resolves the same as that service's own future
+/// would) or to report it shed, expired, or abandoned.
+pub enum BoundedBufferFuture<F, E> {
+    Queued(oneshot::Receiver<Result<F, BoundedBufferError<E>>>),
+    Dispatched(F),
+    Done(Option<BoundedBufferError<E>>),
+}
+
+impl<F, E> Future for BoundedBufferFuture<F, E>
+where
+    F: Future<Error = E>,
+{
+    type Item = F::Item;
+    type Error = BoundedBufferError<E>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match *self {
+                BoundedBufferFuture::Queued(ref mut rx) => match rx.poll() {
+                    Ok(Async::Ready(Ok(fut))) => BoundedBufferFuture::Dispatched(fut),
+                    Ok(Async::Ready(Err(e))) => BoundedBufferFuture::Done(Some(e)),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(_) => BoundedBufferFuture::Done(Some(BoundedBufferError::WorkerGone)),
+                },
+                BoundedBufferFuture::Dispatched(ref mut fut) => {
+                    return fut.poll().map_err(BoundedBufferError::Inner);
+                }
+                BoundedBufferFuture::Done(ref mut e) => {
+                    return Err(e.take().expect("polled after completion"));
+                }
+            };
+            *self = next;
+        }
+    }
+}
+
+// ===== impl BoxError =====
+
+/// A boxed, cloneable error, threaded through the outbound stack's
+/// `Balance`, `OrigDstFallback`, `Buffer`, `InFlightLimit`, and `Timeout`
+/// layers by a `MapErrBoxed` after each one, so `LogErrors` can log any
+/// of them without hard-coding their exact nesting. Adding a layer to
+/// the stack means adding one `From<_> for BoxError` impl near that
+/// layer, not rewriting `LogErrors`.
+///
+/// Wrapped in an `Arc` (rather than just `Box`) so it can be cloned out
+/// of a `Buffer`'s shared, already-failed future.
+#[derive(Clone)]
+pub struct BoxError(Arc<Box<::std::error::Error + Send + Sync>>);
+
+impl BoxError {
+    fn new<E>(e: E) -> Self
+    where
+        E: ::std::error::Error + Send + Sync + 'static,
+    {
+        BoxError(Arc::new(Box::new(e)))
+    }
+}
+
+impl fmt::Debug for BoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self.0, f)
+    }
+}
+
+impl fmt::Display for BoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self.0, f)
+    }
+}
+
+impl ::std::error::Error for BoxError {
+    fn description(&self) -> &str {
+        "outbound service error"
+    }
+
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// An opaque error rendered from another error's `Debug` output, for the
+/// wrapper error types in this stack we don't own and so can't match on
+/// (or otherwise chain a `source()` into).
+#[derive(Debug)]
+struct DebugError(String);
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ::std::error::Error for DebugError {
+    fn description(&self) -> &str {
+        "outbound service error"
+    }
+}
+
+#[derive(Debug)]
+struct Elapsed {
+    after: Duration,
+}
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out after {:?}", self.after)
+    }
+}
+
+impl ::std::error::Error for Elapsed {
+    fn description(&self) -> &str {
+        "timed out"
+    }
+}
+
+#[derive(Debug)]
+struct NoOrigDst;
+
+impl fmt::Display for NoOrigDst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no original destination to fall back to")
+    }
+}
+
+impl ::std::error::Error for NoOrigDst {
+    fn description(&self) -> &str {
+        "no original destination"
+    }
+}
+
+#[derive(Debug)]
+struct ConnectFailed;
+
+impl fmt::Display for ConnectFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("failed to connect to the original destination")
+    }
+}
+
+impl ::std::error::Error for ConnectFailed {
+    fn description(&self) -> &str {
+        "connect failed"
+    }
+}
+
+impl From<OrigDstFallbackError<BoxError>> for BoxError {
+    fn from(e: OrigDstFallbackError<BoxError>) -> Self {
+        match e {
+            OrigDstFallbackError::Balance(e) => e,
+            OrigDstFallbackError::NoOrigDst => BoxError::new(NoOrigDst),
+            OrigDstFallbackError::Connect(()) => BoxError::new(ConnectFailed),
+        }
+    }
+}
+
+impl From<BoundedBufferError<BoxError>> for BoxError {
+    fn from(e: BoundedBufferError<BoxError>) -> Self {
+        match e {
+            BoundedBufferError::Inner(e) => e,
+            other => BoxError::new(other),
+        }
+    }
+}
+
+impl From<InFlightLimitError<BoxError>> for BoxError {
+    fn from(e: InFlightLimitError<BoxError>) -> Self {
+        BoxError::new(DebugError(format!("{:?}", e)))
+    }
+}
+
+impl From<TimeoutError<BoxError>> for BoxError {
+    fn from(e: TimeoutError<BoxError>) -> Self {
+        match e {
+            TimeoutError::Error(e) => e,
+            TimeoutError::Timeout(after) => BoxError::new(Elapsed { after }),
+        }
+    }
+}
+
+impl From<tower_balance::Error<
+    ReconnectError<tower_h2::client::Error, tower_h2::client::ConnectError<TimeoutError<io::Error>>>,
+    (),
+>> for BoxError {
+    fn from(e: tower_balance::Error<
+        ReconnectError<tower_h2::client::Error, tower_h2::client::ConnectError<TimeoutError<io::Error>>>,
+        (),
+    >) -> Self {
+        BoxError::new(DebugError(format!("{:?}", e)))
+    }
+}
+
+// ===== impl MapErrBoxed =====
+
+/// Adapts a `Service`'s error into `BoxError`. Inserted after each layer
+/// of the outbound stack, this keeps every later layer's own error type
+/// a plain `BoxError` instead of a compounding wrapper around whatever's
+/// beneath it.
+pub struct MapErrBoxed<S> {
+    inner: S,
+}
+
+impl<S> MapErrBoxed<S> {
+    fn new(inner: S) -> Self {
+        MapErrBoxed { inner }
+    }
+}
+
+fn box_error<E: Into<BoxError>>(e: E) -> BoxError {
+    e.into()
+}
+
+impl<S> Service for MapErrBoxed<S>
+where
+    S: Service,
+    S::Error: Into<BoxError>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = futures::future::MapErr<S::Future, fn(S::Error) -> BoxError>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(box_error)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.inner.call(req).map_err(box_error as fn(S::Error) -> BoxError)
+    }
+}
+
+// ===== impl LogErrors
+
+/// Log errors in human format, walking the `source()` chain of whatever
+/// `BoxError` the stack produced, alongside the outbound `BoundedBuffer`'s
+/// running shed/expired totals so an operator reading the log can tell
+/// whether an error is part of a load-shedding episode or a one-off.
+pub
+struct LogErrors<S> {
+    inner: S,
+    buffer_metrics: Arc<BufferMetrics>,
+}
+
+impl<S> LogErrors<S>
+where
+    S: Service<Error = BoxError>,
+{
+    fn new(service: S, buffer_metrics: Arc<BufferMetrics>) -> Self {
+        LogErrors {
+            inner: service,
+            buffer_metrics,
+        }
+    }
+}
+
+impl<S> Service for LogErrors<S>
+where
+    S: Service<Error = BoxError>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(|e| {
+            error!(
+                "bind service error: {} (buffer: {} shed, {} expired)",
+                HumanError(&e),
+                self.buffer_metrics.shed_total(),
+                self.buffer_metrics.expired_total(),
+            );
+            e
+        })
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+struct HumanError<'a>(&'a BoxError);
+
+impl<'a> fmt::Display for HumanError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut cause = ::std::error::Error::source(self.0);
+        while let Some(e) = cause {
+            write!(f, ": {}", e)?;
+            cause = e.source();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        format!("127.0.0.1:{}", port).parse().unwrap()
+    }
+
+    #[test]
+    fn stale_addrs_finds_endpoints_dropped_from_a_rotated_set() {
+        let known: HashSet<SocketAddr> = vec![addr(1), addr(2)].into_iter().collect();
+        let fresh: HashSet<SocketAddr> = vec![addr(2), addr(3)].into_iter().collect();
+        assert_eq!(stale_addrs(&known, &fresh), vec![addr(1)]);
+    }
+
+    #[test]
+    fn stale_addrs_is_empty_when_the_set_is_unchanged() {
+        let known: HashSet<SocketAddr> = vec![addr(1), addr(2)].into_iter().collect();
+        assert!(stale_addrs(&known, &known).is_empty());
+    }
+
+    fn breaker_config(max_ejection_percent: f64, consecutive_failures: usize) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            max_ejection_percent,
+            consecutive_failures,
+            base_ejection_time: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn record_failure_waits_for_the_configured_threshold() {
+        let totals = Arc::new(Totals::default());
+        let state = EndpointState::new(totals.clone());
+        let config = breaker_config(1.0, 2);
+
+        state.record_failure(&config);
+        assert!(!state.is_ejected(), "shouldn't eject before consecutive_failures is reached");
+
+        state.record_failure(&config);
+        assert!(state.is_ejected());
+        assert_eq!(totals.ejected.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn record_failure_respects_max_ejection_percent() {
+        let totals = Arc::new(Totals::default());
+        let a = EndpointState::new(totals.clone());
+        let b = EndpointState::new(totals.clone());
+        let config = breaker_config(0.5, 1);
+
+        a.record_failure(&config);
+        assert!(a.is_ejected());
+        assert_eq!(totals.ejected.load(Ordering::Relaxed), 1);
+
+        // Ejecting `b` too would put 2 of 2 endpoints down, over the
+        // configured 50% cap, so it stays in rotation instead.
+        b.record_failure(&config);
+        assert!(!b.is_ejected());
+        assert_eq!(totals.ejected.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn evict_releases_a_live_endpoints_slot_in_totals() {
+        let totals = Arc::new(Totals::default());
+        let state = EndpointState::new(totals.clone());
+        assert_eq!(totals.total.load(Ordering::Relaxed), 1);
+
+        state.evict();
+        assert_eq!(totals.total.load(Ordering::Relaxed), 0);
+        assert_eq!(totals.ejected.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn evict_also_releases_an_ejected_endpoints_slot() {
+        let totals = Arc::new(Totals::default());
+        let state = EndpointState::new(totals.clone());
+        let config = breaker_config(1.0, 1);
+
+        state.record_failure(&config);
+        assert!(state.is_ejected());
+        assert_eq!(totals.ejected.load(Ordering::Relaxed), 1);
+
+        state.evict();
+        assert_eq!(totals.total.load(Ordering::Relaxed), 0);
+        assert_eq!(
+            totals.ejected.load(Ordering::Relaxed), 0,
+            "evicting an ejected endpoint must also release its ejected slot",
+        );
+    }
+}